@@ -14,13 +14,18 @@
 
 use crate::opengl;
 use cgmath::{
-    Decomposed, Deg, InnerSpace, Matrix4, One, PerspectiveFov, Quaternion, Rad, Rotation,
-    Rotation3, Transform, Vector3, Zero,
+    Angle, Decomposed, Deg, InnerSpace, Matrix3, Matrix4, One, PerspectiveFov, Quaternion, Rad,
+    Rotation, Rotation3, Transform, Vector3, VectorSpace, Zero,
 };
 use serde_derive::{Deserialize, Serialize};
 use std::f32;
+use std::f32::consts::FRAC_PI_2;
 use time;
 
+// A hair below 90 degrees, used to clamp pitch so the forward vector stays well-defined and the
+// view never flips upside down.
+const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+
 #[derive(Debug)]
 struct RotationAngle {
     theta: Rad<f32>,
@@ -36,8 +41,35 @@ impl RotationAngle {
     }
 }
 
+// Builds the rotation that makes the camera's local -z axis point along `forward`, keeping the
+// world y axis as "up". Used by orbit mode, where the rotation is derived from the look direction
+// towards the pivot rather than integrated from theta/phi directly.
+fn look_at(forward: Vector3<f32>) -> Quaternion<f32> {
+    let forward = forward.normalize();
+    let right = forward.cross(Vector3::unit_y()).normalize();
+    let up = right.cross(forward);
+    Quaternion::from(Matrix3::from_cols(right, up, -forward))
+}
+
+// The interaction mode a `Camera` is currently in. `FreeFlight` moves the camera itself through
+// the world; `Orbit` keeps it pointed at and circling a fixed `orbit_target`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum CameraMode {
+    FreeFlight,
+    Orbit,
+}
+
+/// The transient input state that drives a `Camera`. Keyboard state (`moving_*`/`turning_*`) is
+/// expected to persist across frames, toggled as keys are pressed and released. Mouse events and
+/// explicit `pan`/`rotate` calls accumulate into this struct and are consumed by `Camera::update`;
+/// call `reset` afterwards to clear them before the next frame.
+///
+/// Separating this from `Camera` lets the same pose be driven by different sources - live
+/// keyboard/mouse input, a test harness calling its methods, or anything else that
+/// can produce a `CameraInput` - and lets the pose-update math in `update` be tested without
+/// touching real input devices.
 #[derive(Debug)]
-pub struct Camera {
+pub struct CameraInput {
     pub moving_backward: bool,
     pub moving_forward: bool,
     pub moving_left: bool,
@@ -48,22 +80,116 @@ pub struct Camera {
     pub turning_right: bool,
     pub turning_down: bool,
     pub turning_up: bool,
+
+    // Accumulated pixel deltas from mouse_drag_pan/mouse_drag_rotate calls since the last reset.
+    drag_pan: (i32, i32),
+    drag_rotate: (i32, i32),
+
+    // Accumulated mouse wheel ticks since the last reset.
+    wheel_delta: i32,
+
+    // A pan/rotation speed contributed directly via `pan`/`rotate`, e.g. from a joystick. Scaled
+    // by the frame time in `Camera::update`.
+    pan: Vector3<f32>,
+    rotation_speed: RotationAngle,
+}
+
+impl CameraInput {
+    pub fn new() -> Self {
+        CameraInput {
+            moving_backward: false,
+            moving_forward: false,
+            moving_left: false,
+            moving_right: false,
+            moving_down: false,
+            moving_up: false,
+            turning_left: false,
+            turning_right: false,
+            turning_down: false,
+            turning_up: false,
+            drag_pan: (0, 0),
+            drag_rotate: (0, 0),
+            wheel_delta: 0,
+            pan: Vector3::zero(),
+            rotation_speed: RotationAngle::zero(),
+        }
+    }
+
+    /// Clears the per-frame accumulators (mouse drags, wheel ticks, explicit pan/rotate). Call
+    /// this once `Camera::update` has consumed them. The persistent `moving_*`/`turning_*` key
+    /// state is left untouched.
+    pub fn reset(&mut self) {
+        self.drag_pan = (0, 0);
+        self.drag_rotate = (0, 0);
+        self.wheel_delta = 0;
+        self.pan = Vector3::zero();
+        self.rotation_speed = RotationAngle::zero();
+    }
+
+    pub fn mouse_drag_pan(&mut self, delta_x: i32, delta_y: i32) {
+        self.drag_pan.0 += delta_x;
+        self.drag_pan.1 += delta_y;
+    }
+
+    pub fn mouse_drag_rotate(&mut self, delta_x: i32, delta_y: i32) {
+        self.drag_rotate.0 += delta_x;
+        self.drag_rotate.1 += delta_y;
+    }
+
+    pub fn mouse_wheel(&mut self, delta: i32) {
+        self.wheel_delta += delta;
+    }
+
+    pub fn pan(&mut self, x: f32, y: f32, z: f32) {
+        self.pan.x += x;
+        self.pan.y += y;
+        self.pan.z += z;
+    }
+
+    pub fn rotate(&mut self, up: f32, around: f32) {
+        self.rotation_speed.phi += Rad(up);
+        self.rotation_speed.theta += Rad(around);
+    }
+}
+
+impl Default for CameraInput {
+    fn default() -> Self {
+        CameraInput::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct Camera {
     pub width: i32,
     pub height: i32,
 
     movement_speed: f32,
     theta: Rad<f32>,
     phi: Rad<f32>,
-    pan: Vector3<f32>,
 
-    // The speed we currently want to rotate at. This is multiplied with the seconds since the last
-    // frame to get to an absolute rotation.
-    rotation_speed: RotationAngle,
+    // The current velocity of the camera, accumulated from thrust and decayed towards zero each
+    // frame. This is what gives flying around a feeling of momentum instead of an instant
+    // start/stop.
+    velocity: Vector3<f32>,
+
+    // The acceleration applied while a movement key is held, in units per second squared.
+    thrust_mag: f32,
+
+    // The time it takes the velocity to decay to half of its current value, in seconds. Smaller
+    // values stop the camera faster once keys are released.
+    damper_half_life: f32,
+
+    mode: CameraMode,
+
+    // The world-space point the camera orbits around in `CameraMode::Orbit`.
+    orbit_target: Vector3<f32>,
 
-    // An absolute value that we should rotate around. This is used when the user is clicking and
-    // dragging with the mouse, at which point we want to follow the mouse and ignore rotation
-    // speed from the Joystick.
-    delta_rotation: RotationAngle,
+    // The distance from `orbit_target` to the camera in `CameraMode::Orbit`. Dollied in and out
+    // with the mouse wheel.
+    radius: f32,
+
+    // The currently playing keyframe animation, if any. User input is ignored while this is set.
+    path: Option<PathPlayback>,
 
     moved: bool,
     transform: Decomposed<Vector3<f32>, Quaternion<f32>>,
@@ -78,26 +204,44 @@ pub struct State {
     theta: Rad<f32>,
 }
 
+/// A single pose in a `Camera::play_path` animation, together with the time it takes to travel
+/// from this pose to the next one. When the path loops, the last keyframe's `duration` is used
+/// for the closing segment back to the first keyframe; it is unused otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub state: State,
+    pub duration: time::Duration,
+}
+
+// The state of an in-progress `play_path` animation.
+#[derive(Debug)]
+struct PathPlayback {
+    keyframes: Vec<Keyframe>,
+    loop_playback: bool,
+    segment: usize,
+    segment_elapsed: time::Duration,
+}
+
 impl Camera {
-    pub fn new(gl: &opengl::Gl, width: i32, height: i32) -> Self {
+    pub fn new(
+        gl: &opengl::Gl,
+        width: i32,
+        height: i32,
+        thrust_mag: f32,
+        damper_half_life: f32,
+    ) -> Self {
         let mut camera = Camera {
             movement_speed: 10.,
-            moving_backward: false,
-            moving_forward: false,
-            moving_left: false,
-            moving_right: false,
-            moving_down: false,
-            moving_up: false,
-            turning_left: false,
-            turning_right: false,
-            turning_down: false,
-            turning_up: false,
             moved: true,
             theta: Rad::zero(),
             phi: Rad::zero(),
-            pan: Vector3::zero(),
-            rotation_speed: RotationAngle::zero(),
-            delta_rotation: RotationAngle::zero(),
+            velocity: Vector3::zero(),
+            thrust_mag,
+            damper_half_life,
+            mode: CameraMode::FreeFlight,
+            orbit_target: Vector3::zero(),
+            radius: 150.,
+            path: None,
             transform: Decomposed {
                 scale: 1.,
                 rot: Quaternion::one(),
@@ -128,6 +272,38 @@ impl Camera {
         self.moved = true;
     }
 
+    /// Sets the point the camera orbits around while in orbit mode.
+    pub fn set_orbit_target(&mut self, target: Vector3<f32>) {
+        self.orbit_target = target;
+        self.moved = true;
+    }
+
+    /// Switches between free-flight and orbit mode.
+    pub fn toggle_camera_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::FreeFlight => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::FreeFlight,
+        };
+        self.moved = true;
+    }
+
+    /// Starts animating the camera through `keyframes` in order, interpolating the pose over each
+    /// keyframe's `duration`. User input is ignored until the path finishes (or forever, if
+    /// `loop_playback` is set). This is the playback counterpart to the poses saved via `state()`.
+    pub fn play_path(&mut self, keyframes: Vec<Keyframe>, loop_playback: bool) {
+        self.path = if keyframes.len() >= 2 {
+            Some(PathPlayback {
+                keyframes,
+                loop_playback,
+                segment: 0,
+                segment_elapsed: time::Duration::zero(),
+            })
+        } else {
+            None
+        };
+        self.moved = true;
+    }
+
     pub fn set_size(&mut self, gl: &opengl::Gl, width: i32, height: i32) {
         self.width = width;
         self.height = height;
@@ -148,112 +324,288 @@ impl Camera {
         self.projection_matrix * world_to_camera
     }
 
-    /// Update the camera position for the current frame. Returns true if the camera moved in this
-    /// step.
-    pub fn update(&mut self, elapsed: time::Duration) -> bool {
+    /// Update the camera position for the current frame from the accumulated `input`. Returns
+    /// true if the camera moved in this step.
+    pub fn update(&mut self, input: &CameraInput, elapsed: time::Duration) -> bool {
+        if self.path.is_some() {
+            return self.update_path(elapsed);
+        }
+
         let mut moved = self.moved;
         self.moved = false;
 
         // Handle keyboard input
-        let mut pan = Vector3::zero();
-        if self.moving_right {
-            pan.x += 1.;
-        }
-        if self.moving_left {
-            pan.x -= 1.;
+        let mut direction = Vector3::zero();
+        if input.moving_right {
+            direction.x += 1.;
         }
-        if self.moving_backward {
-            pan.z += 1.;
+        if input.moving_left {
+            direction.x -= 1.;
         }
-        if self.moving_forward {
-            pan.z -= 1.;
+        if input.moving_backward {
+            direction.z += 1.;
         }
-        if self.moving_up {
-            pan.y += 1.;
+        if input.moving_forward {
+            direction.z -= 1.;
         }
-        if self.moving_down {
-            pan.y -= 1.;
+        if input.moving_up {
+            direction.y += 1.;
         }
-        if pan.magnitude2() > 0. {
-            self.pan += pan.normalize();
+        if input.moving_down {
+            direction.y -= 1.;
         }
 
         let elapsed_seconds = elapsed.num_milliseconds() as f32 / 1000.;
 
+        // Accelerate towards the requested direction, then let the velocity coast and decay
+        // exponentially. This gives the camera momentum instead of snapping to a stop the instant
+        // a key is released.
+        if direction.magnitude2() > 0. {
+            self.velocity += direction.normalize() * self.thrust_mag * elapsed_seconds;
+        }
+        let k = f32::consts::LN_2 / self.damper_half_life;
+        self.velocity *= (-k * elapsed_seconds).exp();
+
         const TURNING_SPEED: Rad<f32> = Rad(0.15);
-        if self.turning_left {
-            self.rotation_speed.theta += TURNING_SPEED;
+        let mut rotation_speed = RotationAngle::zero();
+        if input.turning_left {
+            rotation_speed.theta += TURNING_SPEED;
         }
-        if self.turning_right {
-            self.rotation_speed.theta -= TURNING_SPEED;
+        if input.turning_right {
+            rotation_speed.theta -= TURNING_SPEED;
         }
-        if self.turning_up {
-            self.rotation_speed.phi += TURNING_SPEED;
+        if input.turning_up {
+            rotation_speed.phi += TURNING_SPEED;
         }
-        if self.turning_down {
-            self.rotation_speed.phi -= TURNING_SPEED;
+        if input.turning_down {
+            rotation_speed.phi -= TURNING_SPEED;
+        }
+        rotation_speed.theta += input.rotation_speed.theta;
+        rotation_speed.phi += input.rotation_speed.phi;
+
+        // An absolute value that we should rotate around. This is used when the user is clicking
+        // and dragging with the mouse, at which point we want to follow the mouse and ignore
+        // rotation speed from the keyboard/joystick.
+        let delta_rotation = RotationAngle {
+            theta: -Rad(2. * f32::consts::PI * input.drag_rotate.0 as f32 / self.width as f32),
+            phi: -Rad(2. * f32::consts::PI * input.drag_rotate.1 as f32 / self.height as f32),
+        };
+
+        let mut pan = input.pan;
+        pan.x -= 100. * input.drag_pan.0 as f32 / self.width as f32;
+        pan.y += 100. * input.drag_pan.1 as f32 / self.height as f32;
+
+        // Apply one 10% step per accumulated tick (not just one step for the net sign), so several
+        // ticks received within a single frame compound the same way consecutive per-event
+        // `mouse_wheel` calls used to.
+        if input.wheel_delta != 0 {
+            let sign = input.wheel_delta.signum() as f32;
+            for _ in 0..input.wheel_delta.abs() {
+                match self.mode {
+                    CameraMode::FreeFlight => {
+                        self.movement_speed += sign * 0.1 * self.movement_speed;
+                        self.movement_speed = self.movement_speed.max(0.01);
+                    }
+                    CameraMode::Orbit => {
+                        self.radius += sign * 0.1 * self.radius;
+                        self.radius = self.radius.max(0.01);
+                        moved = true;
+                    }
+                }
+            }
         }
 
         // Apply changes
-        if self.pan.magnitude2() > 0. {
-            moved = true;
-            let translation = self
-                .transform
-                .rot
-                .rotate_vector(self.pan * self.movement_speed * elapsed_seconds);
-            self.transform.disp += translation;
+        if self.mode == CameraMode::FreeFlight {
+            if pan.magnitude2() > 0. {
+                moved = true;
+                let translation = self
+                    .transform
+                    .rot
+                    .rotate_vector(pan * self.movement_speed * elapsed_seconds);
+                self.transform.disp += translation;
+            }
+
+            const VELOCITY_EPSILON: f32 = 1e-6;
+            if self.velocity.magnitude2() > VELOCITY_EPSILON {
+                moved = true;
+                let translation = self
+                    .transform
+                    .rot
+                    .rotate_vector(self.velocity * elapsed_seconds);
+                self.transform.disp += translation;
+            }
+        } else {
+            // In orbit mode, dragging pans the pivot instead of the camera itself. Scale by
+            // movement_speed/elapsed_seconds the same way the FreeFlight branch above does, so
+            // panning feels consistent between the two modes for the same mouse movement.
+            if pan.magnitude2() > 0. {
+                moved = true;
+                let right = self.transform.rot.rotate_vector(Vector3::unit_x());
+                let up = self.transform.rot.rotate_vector(Vector3::unit_y());
+                let scaled_pan = pan * self.movement_speed * elapsed_seconds;
+                self.orbit_target += right * scaled_pan.x + up * scaled_pan.y;
+            }
         }
 
-        if !self.rotation_speed.theta.is_zero()
-            || !self.rotation_speed.phi.is_zero()
-            || !self.delta_rotation.theta.is_zero()
-            || !self.delta_rotation.phi.is_zero()
+        if !rotation_speed.theta.is_zero()
+            || !rotation_speed.phi.is_zero()
+            || !delta_rotation.theta.is_zero()
+            || !delta_rotation.phi.is_zero()
         {
             moved = true;
-            if !self.delta_rotation.theta.is_zero() || !self.delta_rotation.phi.is_zero() {
-                self.theta += self.delta_rotation.theta;
-                self.phi += self.delta_rotation.phi;
+            if !delta_rotation.theta.is_zero() || !delta_rotation.phi.is_zero() {
+                self.theta += delta_rotation.theta;
+                self.phi += delta_rotation.phi;
             } else {
-                self.theta += self.rotation_speed.theta * elapsed_seconds;
-                self.phi += self.rotation_speed.phi * elapsed_seconds;
+                self.theta += rotation_speed.theta * elapsed_seconds;
+                self.phi += rotation_speed.phi * elapsed_seconds;
             }
+            // Keep the pitch just short of vertical so the forward vector never flips upside
+            // down. Yaw is left free to wrap around.
+            self.phi = Rad(self.phi.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
             let rotation_z = Quaternion::from_angle_z(self.theta);
             let rotation_x = Quaternion::from_angle_x(self.phi);
             self.transform.rot = rotation_z * rotation_x;
         }
 
-        self.pan = Vector3::zero();
-        self.rotation_speed.theta = Rad::zero();
-        self.rotation_speed.phi = Rad::zero();
-        self.delta_rotation.theta = Rad::zero();
-        self.delta_rotation.phi = Rad::zero();
+        // In orbit mode the position is derived from theta/phi/radius/orbit_target rather than
+        // integrated directly, so recompute it whenever any of those inputs changed.
+        if self.mode == CameraMode::Orbit && moved {
+            let offset = Vector3::new(
+                self.phi.cos() * self.theta.sin(),
+                self.phi.sin(),
+                self.phi.cos() * self.theta.cos(),
+            ) * self.radius;
+            self.transform.disp = self.orbit_target + offset;
+            // The camera must always look back at the pivot, which the free-flight Euler
+            // composition above does not guarantee (it only encodes theta/phi, not the direction
+            // towards orbit_target). Build the rotation from the actual forward vector instead.
+            self.transform.rot = look_at(-offset);
+        }
+
         moved
     }
 
-    pub fn mouse_drag_pan(&mut self, delta_x: i32, delta_y: i32) {
-        self.pan.x -= 100. * delta_x as f32 / self.width as f32;
-        self.pan.y += 100. * delta_y as f32 / self.height as f32;
+    // Advances the active keyframe path by `elapsed` and interpolates the pose into `self`.
+    // Always reports the camera as moved, since a path is either animating or has just finished.
+    fn update_path(&mut self, elapsed: time::Duration) -> bool {
+        let finished_state = {
+            let path = self.path.as_mut().unwrap();
+            path.segment_elapsed = path.segment_elapsed + elapsed;
+
+            let duration = path.keyframes[path.segment].duration;
+            let t = (path.segment_elapsed.num_milliseconds() as f32
+                / duration.num_milliseconds() as f32)
+                .min(1.);
+            let eased = t * t * (3. - 2. * t);
+
+            let from = path.keyframes[path.segment].state;
+            let to = path.keyframes[(path.segment + 1) % path.keyframes.len()].state;
+            self.transform.disp = from.transform.disp.lerp(to.transform.disp, eased);
+            self.transform.rot = from.transform.rot.slerp(to.transform.rot, eased);
+            self.theta = Rad(from.theta.0 + (to.theta.0 - from.theta.0) * eased);
+            self.phi = Rad(from.phi.0 + (to.phi.0 - from.phi.0) * eased);
+
+            if t >= 1. {
+                path.segment_elapsed = path.segment_elapsed - duration;
+                path.segment += 1;
+                // When looping, the last keyframe gets its own closing segment back to the first
+                // one (using the last keyframe's `duration`), so the playback is a continuous
+                // cycle instead of snapping from the last pose back to the first.
+                let last_segment = if path.loop_playback {
+                    path.keyframes.len() - 1
+                } else {
+                    path.keyframes.len() - 2
+                };
+                if path.segment > last_segment {
+                    if path.loop_playback {
+                        path.segment = 0;
+                        None
+                    } else {
+                        Some(to)
+                    }
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        };
+
+        if finished_state.is_some() {
+            self.path = None;
+        }
+        self.moved = false;
+        true
     }
+}
 
-    pub fn mouse_drag_rotate(&mut self, delta_x: i32, delta_y: i32) {
-        self.delta_rotation.theta -= Rad(2. * f32::consts::PI * delta_x as f32 / self.width as f32);
-        self.delta_rotation.phi -= Rad(2. * f32::consts::PI * delta_y as f32 / self.height as f32);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a `Camera` directly, bypassing `new`/`set_size` so tests don't need a real
+    // `opengl::Gl` context to exercise the pose-update math in `update`.
+    fn test_camera() -> Camera {
+        Camera {
+            movement_speed: 10.,
+            theta: Rad::zero(),
+            phi: Rad::zero(),
+            velocity: Vector3::zero(),
+            thrust_mag: 20.,
+            damper_half_life: 0.1,
+            mode: CameraMode::FreeFlight,
+            orbit_target: Vector3::zero(),
+            radius: 10.,
+            path: None,
+            moved: false,
+            transform: Decomposed {
+                scale: 1.,
+                rot: Quaternion::one(),
+                disp: Vector3::new(0., 0., 10.),
+            },
+            projection_matrix: One::one(),
+            width: 800,
+            height: 600,
+        }
     }
 
-    pub fn mouse_wheel(&mut self, delta: i32) {
-        let sign = delta.signum() as f32;
-        self.movement_speed += sign * 0.1 * self.movement_speed;
-        self.movement_speed = self.movement_speed.max(0.01);
+    #[test]
+    fn pitch_clamps_before_flipping_upside_down() {
+        let mut camera = test_camera();
+        let mut input = CameraInput::new();
+        input.rotate(10., 0.);
+        camera.update(&input, time::Duration::milliseconds(1000));
+        assert!((camera.phi.0 - SAFE_FRAC_PI_2).abs() < 1e-4);
     }
 
-    pub fn pan(&mut self, x: f32, y: f32, z: f32) {
-        self.pan.x += x;
-        self.pan.y += y;
-        self.pan.z += z;
+    #[test]
+    fn velocity_decays_towards_zero_once_keys_are_released() {
+        let mut camera = test_camera();
+        let mut input = CameraInput::new();
+        input.moving_forward = true;
+        camera.update(&input, time::Duration::milliseconds(500));
+        assert!(camera.velocity.magnitude2() > 0.);
+
+        input.moving_forward = false;
+        for _ in 0..50 {
+            camera.update(&input, time::Duration::milliseconds(100));
+        }
+        assert!(camera.velocity.magnitude2() < 1e-4);
     }
 
-    pub fn rotate(&mut self, up: f32, around: f32) {
-        self.rotation_speed.phi += Rad(up);
-        self.rotation_speed.theta += Rad(around);
+    #[test]
+    fn orbit_mode_keeps_camera_facing_the_target() {
+        let mut camera = test_camera();
+        camera.mode = CameraMode::Orbit;
+        camera.set_orbit_target(Vector3::new(1., 2., 3.));
+        let mut input = CameraInput::new();
+        input.mouse_drag_rotate(200, 50);
+        camera.update(&input, time::Duration::milliseconds(16));
+
+        let forward = camera.transform.rot.rotate_vector(-Vector3::unit_z());
+        let to_target = (camera.orbit_target - camera.transform.disp).normalize();
+        assert!(forward.dot(to_target) > 0.99);
     }
 }